@@ -0,0 +1,20 @@
+/// Anchor corner for a floating window, as sent by `win_float_pos`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anchor {
+    NorthWest,
+    NorthEast,
+    SouthWest,
+    SouthEast,
+}
+
+impl Anchor {
+    pub fn parse(anchor: &str) -> Result<Self, String> {
+        match anchor {
+            "NW" => Ok(Anchor::NorthWest),
+            "NE" => Ok(Anchor::NorthEast),
+            "SW" => Ok(Anchor::SouthWest),
+            "SE" => Ok(Anchor::SouthEast),
+            _ => Err(format!("Unknown anchor {}", anchor)),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use super::anchor::Anchor;
+use super::redraw_handler::GridLineCell;
+
+/// One screen cell inside a `Grid`'s buffer: the text plus the highlight id
+/// to paint it with (looked up in a `HighlightTable` at draw time).
+#[derive(Clone)]
+pub struct GridCell {
+    pub text: String,
+    pub hl_id: u64,
+}
+
+impl Default for GridCell {
+    fn default() -> Self {
+        GridCell {
+            text: " ".to_owned(),
+            hl_id: 0,
+        }
+    }
+}
+
+/// Where and how a grid is composited onto the output surface, as tracked
+/// by `win_pos`/`win_float_pos`/`win_external_pos`/`win_hide`.
+#[derive(Clone, Copy)]
+pub enum GridPos {
+    /// Not yet positioned, or hidden by `win_hide`.
+    Unset,
+    /// A plain window, placed at `(start_row, start_col)` on its base grid.
+    Normal { start_row: u64, start_col: u64 },
+    /// A floating window anchored to a corner of another grid, stacked by
+    /// `zindex`.
+    Float {
+        anchor: Anchor,
+        anchor_grid: u64,
+        anchor_row: f64,
+        anchor_col: f64,
+        zindex: i64,
+    },
+    /// Rendered in its own top-level OS window, outside the compositor.
+    External,
+}
+
+/// One `ext_multigrid` grid: its own screen buffer (replacing the single
+/// global `ui_model` the legacy protocol implied) plus its placement.
+pub struct Grid {
+    pub id: u64,
+    pub width: u64,
+    pub height: u64,
+    pub pos: GridPos,
+    cells: Vec<Vec<GridCell>>,
+    /// Last hl_id seen per row, so a cell with no `hl_id` of its own resumes
+    /// the previous cell's id *within that row* without leaking into other
+    /// rows or surviving a `grid_clear`.
+    last_hl_id: Vec<u64>,
+}
+
+impl Grid {
+    pub fn new(id: u64, width: u64, height: u64) -> Self {
+        Grid {
+            id,
+            width,
+            height,
+            pos: GridPos::Unset,
+            cells: Self::blank_cells(width, height),
+            last_hl_id: vec![0; height as usize],
+        }
+    }
+
+    fn blank_cells(width: u64, height: u64) -> Vec<Vec<GridCell>> {
+        vec![vec![GridCell::default(); width as usize]; height as usize]
+    }
+
+    pub fn resize(&mut self, width: u64, height: u64) {
+        self.width = width;
+        self.height = height;
+        self.cells = Self::blank_cells(width, height);
+        self.last_hl_id = vec![0; height as usize];
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = Self::blank_cells(self.width, self.height);
+        self.last_hl_id = vec![0; self.height as usize];
+    }
+
+    pub fn row(&self, row: u64) -> Option<&[GridCell]> {
+        self.cells.get(row as usize).map(|line| line.as_slice())
+    }
+
+    /// Apply a `grid_line` update, resolving any cell with no `hl_id` of its
+    /// own to the most recently seen id *on this row* -- which, per
+    /// `ext_linegrid`, can carry over from an earlier `grid_line` call for
+    /// the same row (a row is sometimes redrawn in more than one chunk), but
+    /// must never leak in from a different row -- and expanding `repeat`
+    /// runs.
+    pub fn apply_line(&mut self, row: u64, col_start: u64, cells: &[GridLineCell]) {
+        let width = self.width as usize;
+        let row_idx = row as usize;
+        let line = match self.cells.get_mut(row_idx) {
+            Some(line) => line,
+            None => return,
+        };
+        let last_hl_id = match self.last_hl_id.get_mut(row_idx) {
+            Some(last_hl_id) => last_hl_id,
+            None => return,
+        };
+
+        let mut col = col_start as usize;
+        for cell in cells {
+            if let Some(hl_id) = cell.hl_id {
+                *last_hl_id = hl_id;
+            }
+
+            for _ in 0..cell.repeat.max(1) {
+                if col >= width {
+                    break;
+                }
+
+                line[col] = GridCell {
+                    text: cell.text.clone(),
+                    hl_id: *last_hl_id,
+                };
+                col += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(text: &str, hl_id: Option<u64>) -> GridLineCell {
+        GridLineCell {
+            text: text.to_owned(),
+            hl_id,
+            repeat: 1,
+        }
+    }
+
+    #[test]
+    fn an_untagged_cell_does_not_inherit_the_previous_rows_hl_id() {
+        let mut grid = Grid::new(1, 4, 2);
+
+        grid.apply_line(0, 0, &[cell("a", Some(9))]);
+        grid.apply_line(1, 0, &[cell("b", None)]);
+
+        assert_eq!(grid.row(0).unwrap()[0].hl_id, 9);
+        assert_eq!(grid.row(1).unwrap()[0].hl_id, 0);
+    }
+
+    #[test]
+    fn an_untagged_cell_resumes_the_previous_chunks_id_within_the_same_row() {
+        let mut grid = Grid::new(1, 4, 1);
+
+        grid.apply_line(0, 0, &[cell("a", Some(9))]);
+        grid.apply_line(0, 1, &[cell("b", None)]);
+
+        assert_eq!(grid.row(0).unwrap()[1].hl_id, 9);
+    }
+}
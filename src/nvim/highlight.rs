@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use neovim_lib::Value;
+
+/// A single highlight attribute definition, as registered by `hl_attr_define`.
+///
+/// `ext_linegrid` sends these once per highlight id instead of streaming a
+/// running `highlight_set` state, so `grid_line` cells can look colors and
+/// styles up by `hl_id` directly.
+#[derive(Clone, Default)]
+pub struct Highlight {
+    pub foreground: Option<i64>,
+    pub background: Option<i64>,
+    pub special: Option<i64>,
+    pub reverse: bool,
+    pub italic: bool,
+    pub bold: bool,
+    pub underline: bool,
+    pub undercurl: bool,
+    pub strikethrough: bool,
+}
+
+impl Highlight {
+    pub fn from(rgb_attrs: &HashMap<String, Value>) -> Self {
+        Highlight {
+            foreground: rgb_attrs.get("foreground").and_then(Value::as_i64),
+            background: rgb_attrs.get("background").and_then(Value::as_i64),
+            special: rgb_attrs.get("special").and_then(Value::as_i64),
+            reverse: rgb_attrs
+                .get("reverse")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            italic: rgb_attrs
+                .get("italic")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            bold: rgb_attrs
+                .get("bold")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            underline: rgb_attrs
+                .get("underline")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            undercurl: rgb_attrs
+                .get("undercurl")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            strikethrough: rgb_attrs
+                .get("strikethrough")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Registry of highlight definitions announced by `hl_attr_define`, keyed by
+/// the `hl_id` that `grid_line` cells carry, so colors and styles can be
+/// looked up by id instead of replaying the legacy running `highlight_set`
+/// state.
+#[derive(Default)]
+pub struct HighlightTable(HashMap<u64, Highlight>);
+
+impl HighlightTable {
+    pub fn new() -> Self {
+        HighlightTable(HashMap::new())
+    }
+
+    pub fn define(&mut self, id: u64, highlight: Highlight) {
+        self.0.insert(id, highlight);
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Highlight> {
+        self.0.get(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_returns_the_most_recently_defined_highlight_for_an_id() {
+        let mut table = HighlightTable::new();
+        let mut attrs = HashMap::new();
+        attrs.insert("foreground".to_owned(), Value::from(42));
+        table.define(7, Highlight::from(&attrs));
+
+        assert_eq!(table.get(7).and_then(|hl| hl.foreground), Some(42));
+        assert!(table.get(8).is_none());
+
+        let mut redefined = HashMap::new();
+        redefined.insert("bold".to_owned(), Value::from(true));
+        table.define(7, Highlight::from(&redefined));
+
+        assert_eq!(table.get(7).and_then(|hl| hl.foreground), None);
+        assert_eq!(table.get(7).map(|hl| hl.bold), Some(true));
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use super::anchor::Anchor;
+use super::grid::{Grid, GridPos};
+
+/// Registry of every live `ext_multigrid` grid, replacing the single global
+/// `ui_model` buffer the legacy protocol assumed, plus the compositor pass
+/// that resolves where each grid should be blitted.
+#[derive(Default)]
+pub struct Multigrid {
+    grids: HashMap<u64, Grid>,
+}
+
+impl Multigrid {
+    pub fn new() -> Self {
+        Multigrid {
+            grids: HashMap::new(),
+        }
+    }
+
+    pub fn resize(&mut self, grid: u64, width: u64, height: u64) {
+        self.grids
+            .entry(grid)
+            .and_modify(|g| g.resize(width, height))
+            .or_insert_with(|| Grid::new(grid, width, height));
+    }
+
+    pub fn destroy(&mut self, grid: u64) {
+        self.grids.remove(&grid);
+    }
+
+    pub fn grid(&self, grid: u64) -> Option<&Grid> {
+        self.grids.get(&grid)
+    }
+
+    pub fn grid_mut(&mut self, grid: u64) -> Option<&mut Grid> {
+        self.grids.get_mut(&grid)
+    }
+
+    pub fn hide(&mut self, grid: u64) {
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            grid.pos = GridPos::Unset;
+        }
+    }
+
+    pub fn set_pos(&mut self, grid: u64, start_row: u64, start_col: u64) {
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            grid.pos = GridPos::Normal {
+                start_row,
+                start_col,
+            };
+        }
+    }
+
+    pub fn set_float_pos(
+        &mut self,
+        grid: u64,
+        anchor: Anchor,
+        anchor_grid: u64,
+        anchor_row: f64,
+        anchor_col: f64,
+        zindex: i64,
+    ) {
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            grid.pos = GridPos::Float {
+                anchor,
+                anchor_grid,
+                anchor_row,
+                anchor_col,
+                zindex,
+            };
+        }
+    }
+
+    pub fn set_external_pos(&mut self, grid: u64) {
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            grid.pos = GridPos::External;
+        }
+    }
+
+    /// Resolve every positioned, on-screen grid's absolute origin relative
+    /// to `base_grid`, back-to-front by `zindex`, for the paint pass to
+    /// blit each grid's buffer in turn. External windows are excluded; the
+    /// platform draws those in their own top-level window.
+    pub fn compositor_order(&self, base_grid: u64) -> Vec<(u64, f64, f64)> {
+        let mut placements: Vec<(u64, f64, f64, i64)> = self
+            .grids
+            .keys()
+            .filter_map(|&id| {
+                let grid = &self.grids[&id];
+                self.resolve_origin(grid, base_grid, &mut vec![grid.id])
+                    .map(|(row, col, zindex)| (id, row, col, zindex))
+            })
+            .collect();
+
+        placements.sort_by_key(|&(_, _, _, zindex)| zindex);
+        placements
+            .into_iter()
+            .map(|(id, row, col, _)| (id, row, col))
+            .collect()
+    }
+
+    /// `visited` guards against a `win_float_pos` anchor chain that cycles
+    /// back on itself (including a grid anchored to itself) -- Neovim may be
+    /// attached over a socket to a separate, possibly misbehaving process, so
+    /// a malformed chain must fall back to "not positioned" rather than
+    /// recurse forever and stack-overflow the whole UI.
+    fn resolve_origin(
+        &self,
+        grid: &Grid,
+        base_grid: u64,
+        visited: &mut Vec<u64>,
+    ) -> Option<(f64, f64, i64)> {
+        match grid.pos {
+            GridPos::Unset | GridPos::External => None,
+            GridPos::Normal {
+                start_row,
+                start_col,
+            } => Some((start_row as f64, start_col as f64, 0)),
+            GridPos::Float {
+                anchor,
+                anchor_grid,
+                anchor_row,
+                anchor_col,
+                zindex,
+            } => {
+                let (anchor_origin_row, anchor_origin_col) = if anchor_grid == base_grid {
+                    (0.0, 0.0)
+                } else {
+                    if visited.contains(&anchor_grid) {
+                        return None;
+                    }
+                    visited.push(anchor_grid);
+
+                    let anchor = self.grids.get(&anchor_grid)?;
+                    let (row, col, _) = self.resolve_origin(anchor, base_grid, visited)?;
+                    (row, col)
+                };
+
+                let (row, col) = match anchor {
+                    Anchor::NorthWest => (anchor_row, anchor_col),
+                    Anchor::NorthEast => (anchor_row, anchor_col - grid.width as f64),
+                    Anchor::SouthWest => (anchor_row - grid.height as f64, anchor_col),
+                    Anchor::SouthEast => (
+                        anchor_row - grid.height as f64,
+                        anchor_col - grid.width as f64,
+                    ),
+                };
+
+                Some((anchor_origin_row + row, anchor_origin_col + col, zindex))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_window_sits_at_its_reported_position() {
+        let mut mg = Multigrid::new();
+        mg.resize(2, 10, 5);
+        mg.set_pos(2, 3, 4);
+
+        assert_eq!(mg.compositor_order(1), vec![(2, 3.0, 4.0)]);
+    }
+
+    #[test]
+    fn float_anchors_offset_by_their_own_size_on_the_se_corner() {
+        let mut mg = Multigrid::new();
+        mg.resize(1, 80, 24); // base grid
+        mg.set_pos(1, 0, 0);
+        mg.resize(2, 10, 4); // floating popup, e.g. a hover doc
+        mg.set_float_pos(2, Anchor::SouthEast, 1, 10.0, 20.0, 50);
+
+        let placements = mg.compositor_order(1);
+        let popup = placements.iter().find(|&&(id, _, _)| id == 2).unwrap();
+
+        // SE anchor: the float's bottom-right corner sits at (10, 20), so
+        // its origin is shifted up/left by its own height/width.
+        assert_eq!((popup.1, popup.2), (6.0, 10.0));
+    }
+
+    #[test]
+    fn floats_are_ordered_back_to_front_by_zindex() {
+        let mut mg = Multigrid::new();
+        mg.resize(1, 80, 24);
+        mg.set_pos(1, 0, 0);
+        mg.resize(2, 10, 4);
+        mg.set_float_pos(2, Anchor::NorthWest, 1, 0.0, 0.0, 100);
+        mg.resize(3, 10, 4);
+        mg.set_float_pos(3, Anchor::NorthWest, 1, 0.0, 0.0, 10);
+
+        let order: Vec<u64> = mg
+            .compositor_order(1)
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect();
+
+        assert_eq!(order, vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn hidden_and_external_grids_are_excluded_from_the_paint_pass() {
+        let mut mg = Multigrid::new();
+        mg.resize(1, 80, 24);
+        mg.set_pos(1, 0, 0);
+        mg.resize(2, 10, 4);
+        mg.set_external_pos(2);
+        mg.resize(3, 10, 4);
+        mg.set_pos(3, 1, 1);
+        mg.hide(3);
+
+        assert_eq!(mg.compositor_order(1), vec![(1, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn a_cyclic_anchor_chain_is_dropped_instead_of_recursing_forever() {
+        let mut mg = Multigrid::new();
+        mg.resize(1, 80, 24);
+        mg.set_pos(1, 0, 0);
+        mg.resize(2, 10, 4);
+        mg.set_float_pos(2, Anchor::NorthWest, 3, 0.0, 0.0, 10);
+        mg.resize(3, 10, 4);
+        mg.set_float_pos(3, Anchor::NorthWest, 2, 0.0, 0.0, 10);
+
+        let order: Vec<u64> = mg
+            .compositor_order(1)
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect();
+
+        assert_eq!(order, vec![1]);
+    }
+}
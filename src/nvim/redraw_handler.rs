@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use neovim_lib::{UiOption, Value};
-use neovim_lib::neovim_api::Tabpage;
+use neovim_lib::neovim_api::{Tabpage, Window};
 
 use ui::UiMutex;
 use shell;
@@ -14,6 +14,8 @@ use rmpv;
 
 use super::repaint_mode::RepaintMode;
 use super::mode_info::ModeInfo;
+use super::anchor::Anchor;
+use super::highlight::Highlight;
 
 pub trait RedrawEvents {
     fn on_cursor_goto(&mut self, row: u64, col: u64) -> RepaintMode;
@@ -97,6 +99,65 @@ pub trait RedrawEvents {
     fn cmdline_pos(&mut self, pos: u64, level: u64) -> RepaintMode;
 
     fn cmdline_special_char(&mut self, c: String, shift: bool, level: u64) -> RepaintMode;
+
+    fn grid_resize(&mut self, grid: u64, width: u64, height: u64) -> RepaintMode;
+
+    fn grid_clear(&mut self, grid: u64) -> RepaintMode;
+
+    fn grid_cursor_goto(&mut self, grid: u64, row: u64, col: u64) -> RepaintMode;
+
+    fn grid_scroll(
+        &mut self,
+        grid: u64,
+        top: u64,
+        bot: u64,
+        left: u64,
+        right: u64,
+        rows: i64,
+        cols: i64,
+    ) -> RepaintMode;
+
+    fn default_colors_set(&mut self, fg: i64, bg: i64, sp: i64) -> RepaintMode;
+
+    fn hl_attr_define(&mut self, id: u64, highlight: Highlight) -> RepaintMode;
+
+    fn grid_line(
+        &mut self,
+        grid: u64,
+        row: u64,
+        col_start: u64,
+        cells: Vec<GridLineCell>,
+    ) -> RepaintMode;
+
+    fn grid_destroy(&mut self, grid: u64) -> RepaintMode;
+
+    fn win_pos(
+        &mut self,
+        grid: u64,
+        win: Window,
+        start_row: u64,
+        start_col: u64,
+        width: u64,
+        height: u64,
+    ) -> RepaintMode;
+
+    fn win_float_pos(
+        &mut self,
+        grid: u64,
+        win: Window,
+        anchor: Anchor,
+        anchor_grid: u64,
+        anchor_row: f64,
+        anchor_col: f64,
+        focusable: bool,
+        zindex: i64,
+    ) -> RepaintMode;
+
+    fn win_external_pos(&mut self, grid: u64, win: Window) -> RepaintMode;
+
+    fn win_hide(&mut self, grid: u64) -> RepaintMode;
+
+    fn win_close(&mut self, grid: u64) -> RepaintMode;
 }
 
 pub trait GuiApi {
@@ -168,6 +229,16 @@ macro_rules! call {
     )
 }
 
+/// UI capabilities to request when attaching to Neovim. `ExtLinegrid` must
+/// be enabled or Neovim keeps sending the legacy global-grid events instead
+/// of the `grid_line`/`grid_scroll`/... events this dispatcher parses, and
+/// `ExtMultigrid` must be enabled or Neovim never sends `win_pos`/
+/// `win_float_pos`/`win_external_pos`/`win_hide`/`win_close`, leaving every
+/// grid on the single base grid and the `Multigrid` compositor unreachable.
+pub fn ui_attach_options() -> Vec<UiOption> {
+    vec![UiOption::ExtLinegrid(true), UiOption::ExtMultigrid(true)]
+}
+
 pub fn call_gui_event(
     ui: &mut shell::State,
     method: &str,
@@ -201,6 +272,7 @@ pub fn call_gui_event(
                     nvim.set_option(UiOption::ExtCmdline(try_uint!(args[1]) == 1))
                         .map_err(|e| e.to_string())
                 })?,
+            "FontFeature" => ui.set_font_feature(try_uint!(args[1]), try_str!(args[2]).to_owned()),
             opt => error!("Unknown option {}", opt),
         },
         _ => return Err(format!("Unsupported event {}({:?})", method, args)),
@@ -324,6 +396,74 @@ pub fn call(
         "cmdline_block_hide" => ui.cmdline_block_hide(),
         "cmdline_pos" => call!(ui->cmdline_pos(args: uint, uint)),
         "cmdline_special_char" => call!(ui->cmdline_special_char(args: str, bool, uint)),
+        "grid_resize" => call!(ui->grid_resize(args: uint, uint, uint)),
+        "grid_clear" => call!(ui->grid_clear(args: uint)),
+        "grid_cursor_goto" => call!(ui->grid_cursor_goto(args: uint, uint, uint)),
+        "grid_scroll" => call!(ui->grid_scroll(args: uint, uint, uint, uint, uint, int, int)),
+        "default_colors_set" => call!(ui->default_colors_set(args: int, int, int)),
+        "hl_attr_define" => {
+            let id = try_uint!(args[0]);
+            let rgb_attrs = args[1]
+                .as_map()
+                .ok_or_else(|| "Error get rgb_attrs map for hl_attr_define".to_owned())
+                .and_then(|m| m.to_attrs_map())?;
+
+            ui.hl_attr_define(id, Highlight::from(&rgb_attrs))
+        }
+        "grid_line" => {
+            let grid = try_uint!(args[0]);
+            let row = try_uint!(args[1]);
+            let col_start = try_uint!(args[2]);
+            let cells = map_array!(
+                args[3],
+                "Error get grid_line cells array".to_owned(),
+                |cell| GridLineCell::from_value(cell)
+            )?;
+
+            ui.grid_line(grid, row, col_start, cells)
+        }
+        "grid_destroy" => call!(ui->grid_destroy(args: uint)),
+        "win_pos" => {
+            let grid = try_uint!(args[0]);
+            let win = Window::new(args[1].clone());
+
+            ui.win_pos(
+                grid,
+                win,
+                try_uint!(args[2]),
+                try_uint!(args[3]),
+                try_uint!(args[4]),
+                try_uint!(args[5]),
+            )
+        }
+        "win_float_pos" => {
+            let grid = try_uint!(args[0]);
+            let win = Window::new(args[1].clone());
+            let anchor = Anchor::parse(try_str!(args[2]))?;
+
+            ui.win_float_pos(
+                grid,
+                win,
+                anchor,
+                try_uint!(args[3]),
+                args[4]
+                    .as_f64()
+                    .ok_or_else(|| "Error get anchor_row for win_float_pos".to_owned())?,
+                args[5]
+                    .as_f64()
+                    .ok_or_else(|| "Error get anchor_col for win_float_pos".to_owned())?,
+                try_bool!(args[6]),
+                try_int!(args[7]),
+            )
+        }
+        "win_external_pos" => {
+            let grid = try_uint!(args[0]);
+            let win = Window::new(args[1].clone());
+
+            ui.win_external_pos(grid, win)
+        }
+        "win_hide" => call!(ui->win_hide(args: uint)),
+        "win_close" => call!(ui->win_close(args: uint)),
         _ => {
             warn!("Event {}({:?})", method, args);
             RepaintMode::Nothing
@@ -352,3 +492,44 @@ impl<'a> CompleteItem<'a> {
             .collect()
     }
 }
+
+/// Single cell update within a `grid_line` event.
+///
+/// Per `ext_linegrid`, each cell arrives as `[text]`, `[text, hl_id]` or
+/// `[text, hl_id, repeat]`; a missing `hl_id` means "reuse the previous
+/// cell's id", which callers resolve by tracking the last seen id themselves.
+pub struct GridLineCell {
+    pub text: String,
+    pub hl_id: Option<u64>,
+    pub repeat: u64,
+}
+
+impl GridLineCell {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let cell = value
+            .as_array()
+            .ok_or_else(|| "Error get grid_line cell array".to_owned())?;
+
+        let text = cell
+            .get(0)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "Error get grid_line cell text".to_owned())?
+            .to_owned();
+
+        let hl_id = match cell.get(1) {
+            Some(v) if !v.is_nil() => Some(
+                v.as_u64()
+                    .ok_or_else(|| "Error get grid_line cell hl_id".to_owned())?,
+            ),
+            _ => None,
+        };
+
+        let repeat = cell.get(2).and_then(|v| v.as_u64()).unwrap_or(1);
+
+        Ok(GridLineCell {
+            text,
+            hl_id,
+            repeat,
+        })
+    }
+}
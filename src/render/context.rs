@@ -1,3 +1,10 @@
+use std::cell::RefCell;
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
 use pango::prelude::*;
 use pango;
 
@@ -7,9 +14,18 @@ use sys::pango::AttrIteratorFactory;
 use ui_model::StyledLine;
 use super::itemize::ItemizeIterator;
 
+/// Entries kept in the itemize cache before the least-recently-used
+/// one is evicted. `grid_scroll` re-requests the same handful of lines over
+/// and over, so this only needs to outlive one screen's worth of lines.
+const ITEMIZE_CACHE_CAPACITY: usize = 512;
+
 pub struct Context {
     font_metrics: FontMetrix,
     font_features: FontFeatures,
+    fallback_fonts: Vec<String>,
+    itemize_cache: RefCell<LruCache<u64, (pango::AttrList, Vec<sys_pango::Item>)>>,
+    #[cfg(test)]
+    itemize_misses: Cell<usize>,
 }
 
 impl Context {
@@ -17,32 +33,122 @@ impl Context {
         Context {
             font_metrics: FontMetrix::new(pango_context),
             font_features: FontFeatures::new(),
+            fallback_fonts: Vec::new(),
+            itemize_cache: RefCell::new(LruCache::new(ITEMIZE_CACHE_CAPACITY)),
+            #[cfg(test)]
+            itemize_misses: Cell::new(0),
         }
     }
 
+    /// Number of `itemize` calls that actually ran `pango_itemize` rather
+    /// than being served from `itemize_cache`.
+    #[cfg(test)]
+    fn itemize_misses(&self) -> usize {
+        self.itemize_misses.get()
+    }
+
     pub fn update(&mut self, pango_context: pango::Context) {
         self.font_metrics = FontMetrix::new(pango_context);
+        self.itemize_cache.borrow_mut().clear();
     }
 
     pub fn update_font_features(&mut self, font_features: FontFeatures) {
         self.font_features = font_features;
+        self.itemize_cache.borrow_mut().clear();
+    }
+
+    /// Set the ordered list of font families to fall back to when the
+    /// primary font (set on the pango context) is missing a glyph, e.g. a
+    /// coding font followed by an emoji font and a CJK font.
+    pub fn set_fallback_fonts(&mut self, fallback_fonts: Vec<String>) {
+        self.fallback_fonts = fallback_fonts;
+        self.itemize_cache.borrow_mut().clear();
     }
 
     pub fn itemize(&self, line: &StyledLine) -> Vec<sys_pango::Item> {
-        let mut attr_iter = line.attr_list.get_iterator();
+        self.itemize_str(&line.line_str, &line.attr_list)
+    }
+
+    fn itemize_str(&self, line_str: &str, attr_list: &pango::AttrList) -> Vec<sys_pango::Item> {
+        let key = itemize_cache_key(line_str, &self.font_metrics.font_desc);
+
+        if let Some((cached_attr_list, items)) = self.itemize_cache.borrow_mut().get(&key) {
+            // `key` only buckets by text and font; different `AttrList`s can
+            // collide on it (e.g. the same text drawn with another
+            // highlight), so confirm the highlight spans
+            // actually match before trusting the cached itemization.
+            if cached_attr_list == *attr_list {
+                return items;
+            }
+        }
 
-        ItemizeIterator::new(&line.line_str)
+        #[cfg(test)]
+        self.itemize_misses.set(self.itemize_misses.get() + 1);
+
+        let mut attr_iter = attr_list.get_iterator();
+
+        let items: Vec<sys_pango::Item> = ItemizeIterator::new(line_str)
             .flat_map(|(offset, len)| {
                 sys_pango::pango_itemize(
                     &self.font_metrics.pango_context,
-                    &line.line_str,
+                    line_str,
                     offset,
                     len,
-                    &line.attr_list,
+                    attr_list,
                     Some(&mut attr_iter),
                 )
             })
-            .collect()
+            .flat_map(|item| self.resolve_fallback(line_str, item))
+            .collect();
+
+        self.itemize_cache
+            .borrow_mut()
+            .insert(key, (attr_list.clone(), items.clone()));
+
+        items
+    }
+
+    /// Re-itemize `item`'s byte range against successive `fallback_fonts`
+    /// entries when its shaped font is missing a glyph for a character in
+    /// that range, stopping at the first family that covers it.
+    fn resolve_fallback(&self, line_str: &str, item: sys_pango::Item) -> Vec<sys_pango::Item> {
+        let (offset, len) = (item.offset(), item.length());
+
+        if self.fallback_fonts.is_empty() || !has_missing_glyphs(line_str, &item, offset, len) {
+            return vec![item];
+        }
+
+        // Carry over the run's own resolved weight/style (e.g. a bold or
+        // italic highlight group) instead of falling back to the bare
+        // default description, or the fallback glyph silently loses it.
+        let run_desc = item
+            .analysis()
+            .font()
+            .describe()
+            .unwrap_or_else(|| self.font_metrics.font_desc.clone());
+
+        for family in &self.fallback_fonts {
+            let attr_list = fallback_attr_list(&run_desc, family);
+            let mut attr_iter = attr_list.get_iterator();
+
+            let items = sys_pango::pango_itemize(
+                &self.font_metrics.pango_context,
+                line_str,
+                offset,
+                len,
+                &attr_list,
+                Some(&mut attr_iter),
+            );
+
+            if items
+                .iter()
+                .all(|item| !has_missing_glyphs(line_str, item, offset, len))
+            {
+                return items;
+            }
+        }
+
+        vec![item]
     }
 
     pub fn create_layout(&self) -> pango::Layout {
@@ -62,6 +168,97 @@ impl Context {
     }
 }
 
+/// True if `font` (the font `pango_itemize` resolved for this run) has no
+/// glyph for some character in `line_str[offset..offset + len]`.
+fn has_missing_glyphs(line_str: &str, item: &sys_pango::Item, offset: usize, len: usize) -> bool {
+    let font = item.analysis().font();
+
+    line_str[offset..offset + len]
+        .chars()
+        .any(|ch| !font.has_char(ch))
+}
+
+/// Build an attribute list that forces `family` over the whole line, for
+/// re-itemizing a run whose primary font is missing a glyph.
+fn fallback_attr_list(font_desc: &pango::FontDescription, family: &str) -> pango::AttrList {
+    let attr_list = pango::AttrList::new();
+
+    let mut desc = font_desc.clone();
+    desc.set_family(family);
+
+    let mut attr = sys_pango::attribute::new_font_desc(&desc).unwrap();
+    attr.set_start_index(0);
+    attr.set_end_index(u32::max_value());
+    attr_list.insert(attr);
+
+    attr_list
+}
+
+/// Coarse bucket key for the itemize cache, hashing `(line_str, font_desc)`.
+/// This alone doesn't prove two lines are identical -- distinct `AttrList`s
+/// for the same text/font can and do collide here -- so `itemize` always
+/// verifies the cached entry's `AttrList` against the line's own before
+/// treating a lookup as a hit.
+fn itemize_cache_key(line_str: &str, font_desc: &pango::FontDescription) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line_str.hash(&mut hasher);
+    font_desc.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A small least-recently-used cache, capped by entry count rather than
+/// memory size since itemize results are cheap relative to the bookkeeping
+/// needed to size them precisely.
+struct LruCache<K: Eq + Hash + Clone, V: Clone> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let value = self.entries.get(key).cloned();
+        if value.is_some() {
+            self.touch(key);
+        }
+        value
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(key.clone(), value);
+        self.touch(&key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn touch(&mut self, key: &K) {
+        self.order.retain(|k| k != key);
+        self.order.push_back(key.clone());
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
 struct FontMetrix {
     pango_context: pango::Context,
     cell_metrics: CellMetrics,
@@ -124,14 +321,122 @@ impl CellMetrics {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pangocairo;
+
+    /// A headless `Context` backed by a real pangocairo font map, so
+    /// `itemize`/`itemize_str` run actual `pango_itemize` calls without
+    /// needing a GTK display.
+    fn test_context() -> Context {
+        let font_map = pangocairo::FontMap::get_default().expect("default pangocairo font map");
+        let pango_context = font_map.create_context().expect("pango context");
+        pango_context.set_font_description(&pango::FontDescription::from_string("Monospace 12"));
+        Context::new(pango_context)
+    }
+
+    #[test]
+    fn repeated_line_is_served_from_cache_instead_of_reitemized() {
+        let ctx = test_context();
+        let attr_list = pango::AttrList::new();
+        let line = "the quick brown fox jumps over the lazy dog";
+
+        let first = ctx.itemize_str(line, &attr_list);
+        assert_eq!(ctx.itemize_misses(), 1);
+
+        // `grid_scroll` re-requests the same handful of lines over and
+        // over; none of these should re-run `pango_itemize`.
+        for _ in 0..20 {
+            let cached = ctx.itemize_str(line, &attr_list);
+            assert_eq!(cached.len(), first.len());
+        }
+        assert_eq!(ctx.itemize_misses(), 1);
+    }
+
+    #[test]
+    fn distinct_attr_lists_with_equal_text_do_not_collide_in_the_cache() {
+        let ctx = test_context();
+        let line = "++++++++++"; // blank/repeated-glyph lines commonly share scratch buffers
+
+        let plain = pango::AttrList::new();
+        let first = ctx.itemize_str(line, &plain);
+        assert_eq!(ctx.itemize_misses(), 1);
+
+        let mut bold_desc = ctx.font_description().clone();
+        bold_desc.set_weight(pango::Weight::Bold);
+        let bold_attrs = pango::AttrList::new();
+        let mut bold_attr = sys_pango::attribute::new_font_desc(&bold_desc).unwrap();
+        bold_attr.set_start_index(0);
+        bold_attr.set_end_index(line.len() as u32);
+        bold_attrs.insert(bold_attr);
+
+        // Same line text and base font, but a genuinely different
+        // `AttrList` -- a cache keyed on pointer identity or on text/font
+        // alone could wrongly hand back `first` here.
+        let second = ctx.itemize_str(line, &bold_attrs);
+        assert_eq!(ctx.itemize_misses(), 2);
+        assert_eq!(first.len(), second.len());
+    }
+
+    // `sys_pango::Item` isn't constructible outside a live pango context, so
+    // these exercise the generic `LruCache` mechanics (hit-reuse, LRU
+    // eviction) directly, hashing `CellMetrics` fixtures of varying cell
+    // size into distinct cache keys.
+    fn cell_metrics_fixture(char_width: f64) -> CellMetrics {
+        CellMetrics::new_hw(20.0, char_width)
+    }
+
+    #[test]
+    fn repeated_lookups_reuse_the_cached_entry() {
+        let mut cache = LruCache::new(4);
+        let key = itemize_cache_key_for(&cell_metrics_fixture(8.0));
+
+        assert!(cache.get(&key).is_none());
+        cache.insert(key, vec![cell_metrics_fixture(8.0).char_width]);
+
+        for _ in 0..10 {
+            assert!(cache.get(&key).is_some());
+        }
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used_entry_past_capacity() {
+        let mut cache = LruCache::new(2);
+
+        let a = itemize_cache_key_for(&cell_metrics_fixture(8.0));
+        let b = itemize_cache_key_for(&cell_metrics_fixture(9.0));
+        let c = itemize_cache_key_for(&cell_metrics_fixture(10.0));
+
+        cache.insert(a, vec![1.0]);
+        cache.insert(b, vec![2.0]);
+        cache.get(&a); // keep `a` fresh, making `b` the least-recently-used
+        cache.insert(c, vec![3.0]);
+
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+        assert_eq!(cache.len(), 2);
+    }
+
+    fn itemize_cache_key_for(metrics: &CellMetrics) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (metrics.char_width as u64).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
 pub struct FontFeatures {
-    features: Option<String>,
+    default: Option<String>,
+    overrides: HashMap<u64, String>,
 }
 
 impl FontFeatures {
     pub fn new() -> Self {
-        FontFeatures {  
-            features: None,
+        FontFeatures {
+            default: None,
+            overrides: HashMap::new(),
         }
     }
 
@@ -141,16 +446,46 @@ impl FontFeatures {
         }
 
         FontFeatures {
-            features: Some(font_features)
+            default: Some(font_features),
+            overrides: HashMap::new(),
         }
     }
 
-    pub fn insert_attr(&self, attr_list: &pango::AttrList, end_idx: usize) {
-        if let Some(ref features) = self.features {
-            let mut attr = sys_pango::attribute::new_features(features).unwrap();
-            attr.set_start_index(0);
-            attr.set_end_index(end_idx as u32);
-            attr_list.insert(attr);
+    /// Set (or, if empty, clear) the font feature string used for runs
+    /// shaped with the `hl_id` highlight group, overriding the default for
+    /// just that group.
+    pub fn set_override(&mut self, hl_id: u64, font_features: String) {
+        if font_features.trim().is_empty() {
+            self.overrides.remove(&hl_id);
+        } else {
+            self.overrides.insert(hl_id, font_features);
+        }
+    }
+
+    /// Insert a `new_features` attribute per itemized run, scoped to that
+    /// run's byte range and resolved to its highlight group's override (or
+    /// the default features, if no override applies).
+    pub fn insert_attr(
+        &self,
+        line: &StyledLine,
+        items: &[sys_pango::Item],
+        attr_list: &pango::AttrList,
+    ) {
+        for item in items {
+            let start_idx = item.offset();
+            let end_idx = start_idx + item.length();
+
+            let features = line
+                .hl_id_at(start_idx)
+                .and_then(|hl_id| self.overrides.get(&hl_id))
+                .or_else(|| self.default.as_ref());
+
+            if let Some(features) = features {
+                let mut attr = sys_pango::attribute::new_features(features).unwrap();
+                attr.set_start_index(start_idx as u32);
+                attr.set_end_index(end_idx as u32);
+                attr_list.insert(attr);
+            }
         }
     }
 }
@@ -0,0 +1,38 @@
+use pango;
+
+/// One rendered screen cell's resolved highlight id.
+#[derive(Clone, Copy, Default)]
+pub struct Cell {
+    pub hl_id: u64,
+}
+
+/// One row of text handed to `Context::itemize`/`FontFeatures::insert_attr`:
+/// the raw text, the `AttrList` built from it for Pango shaping, and the
+/// highlight id spans needed to resolve per-run font-feature/color
+/// overrides by the highlight group each run falls in.
+pub struct StyledLine {
+    pub line_str: String,
+    pub attr_list: pango::AttrList,
+    /// `(start_byte, hl_id)` pairs in ascending `start_byte` order; each id
+    /// applies until the next entry's `start_byte`.
+    hl_spans: Vec<(usize, u64)>,
+}
+
+impl StyledLine {
+    pub fn new(line_str: String, attr_list: pango::AttrList, hl_spans: Vec<(usize, u64)>) -> Self {
+        StyledLine {
+            line_str,
+            attr_list,
+            hl_spans,
+        }
+    }
+
+    /// The highlight id in effect at `byte_idx`, if any span covers it.
+    pub fn hl_id_at(&self, byte_idx: usize) -> Option<u64> {
+        self.hl_spans
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= byte_idx)
+            .map(|&(_, hl_id)| hl_id)
+    }
+}